@@ -1,7 +1,9 @@
-use clap::{Parser, Subcommand};
+use clap::{Parser, Subcommand, ValueEnum};
 use nvml_wrapper::Device;
+use nvml_wrapper::enum_wrappers::device::{Clock, ClockId, TemperatureSensor};
 use nvml_wrapper::enums::device::UsedGpuMemory;
 use nvml_wrapper::{Nvml, error::NvmlError};
+use serde::Deserialize;
 use sysinfo::{Pid, ProcessesToUpdate, System};
 
 use crossterm::{
@@ -11,14 +13,16 @@ use crossterm::{
 };
 
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
+    fs,
     io::{Write, stdout},
+    path::PathBuf,
     sync::{
         Arc,
         atomic::{AtomicBool, Ordering},
     },
     thread,
-    time::Duration,
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
 #[derive(Parser)]
@@ -26,6 +30,100 @@ use std::{
 struct Cli {
     #[command(subcommand)]
     command: Option<Commands>,
+
+    /// Path to a TOML config file with exclude_metrics / exclude_devices
+    #[arg(long, global = true)]
+    config: Option<PathBuf>,
+
+    /// Metric to exclude from collection and display (may be repeated)
+    #[arg(long, value_enum, global = true)]
+    exclude_metric: Vec<Metric>,
+
+    /// GPU index or name substring to exclude (may be repeated)
+    #[arg(long, global = true)]
+    exclude_device: Vec<String>,
+
+    /// How to identify MIG instances in output
+    #[arg(long, value_enum, global = true, default_value_t = MigIdFormat::Slice)]
+    mig_id_format: MigIdFormat,
+
+    /// Field to sort the process table by
+    #[arg(long, value_enum, global = true, default_value_t = SortBy::Memory)]
+    sort_by: SortBy,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum SortBy {
+    /// GPU memory used, descending
+    Memory,
+    Pid,
+    Name,
+    #[value(name = "type")]
+    Type,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum MigIdFormat {
+    /// GPU instance UUID
+    Uuid,
+    /// `<gpu index>/<gpu instance id>` slice notation
+    Slice,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Metric {
+    Temperature,
+    Power,
+    Clocks,
+    Processes,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default)]
+struct Config {
+    exclude_metrics: Vec<Metric>,
+    exclude_devices: Vec<String>,
+}
+
+/// Resolved set of metrics and devices to skip, merged from the config file
+/// (if any) and the `--exclude-metric` / `--exclude-device` flags.
+struct ExcludeFilter {
+    metrics: HashSet<Metric>,
+    devices: Vec<String>,
+}
+
+impl ExcludeFilter {
+    fn load(cli: &Cli) -> Result<Self, String> {
+        let mut config = match &cli.config {
+            Some(path) => {
+                let contents = fs::read_to_string(path)
+                    .map_err(|e| format!("failed to read config file {}: {e}", path.display()))?;
+                toml::from_str(&contents)
+                    .map_err(|e| format!("failed to parse config file {}: {e}", path.display()))?
+            }
+            None => Config::default(),
+        };
+
+        config.exclude_metrics.extend(cli.exclude_metric.iter().copied());
+        config.exclude_devices.extend(cli.exclude_device.iter().cloned());
+
+        Ok(ExcludeFilter {
+            metrics: config.exclude_metrics.into_iter().collect(),
+            devices: config.exclude_devices,
+        })
+    }
+
+    fn excludes_metric(&self, metric: Metric) -> bool {
+        self.metrics.contains(&metric)
+    }
+
+    fn excludes_device(&self, index: u32, name: &str) -> bool {
+        self.devices.iter().any(|d| match d.parse::<u32>() {
+            Ok(excluded_index) => excluded_index == index,
+            Err(_) => name.to_lowercase().contains(&d.to_lowercase()),
+        })
+    }
 }
 
 #[derive(Subcommand)]
@@ -38,14 +136,32 @@ enum Commands {
     },
     /// Displau GPU info
     Info,
+    /// Export metrics for scraping by external collectors
+    Export {
+        /// Output format
+        #[arg(short, long, value_enum, default_value_t = ExportFormat::Line)]
+        format: ExportFormat,
+    },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum ExportFormat {
+    /// InfluxDB line protocol
+    Line,
 }
 
 fn main() -> Result<(), NvmlError> {
     let cli = Cli::parse();
+    let filter = match ExcludeFilter::load(&cli) {
+        Ok(filter) => filter,
+        Err(e) => {
+            eprintln!("grax: {e}");
+            std::process::exit(1);
+        }
+    };
     let mut stdout = stdout();
 
     let nvml = Nvml::init()?;
-    let device = nvml.device_by_index(0)?;
     let mut sys = System::new();
 
     match &cli.command {
@@ -65,7 +181,9 @@ fn main() -> Result<(), NvmlError> {
                 while running.load(Ordering::SeqCst) {
                     sys.refresh_processes(ProcessesToUpdate::All, true);
 
-                    if let Ok(buffer) = get_metrics(&device, &sys) {
+                    if let Ok(buffer) =
+                        get_metrics(&nvml, &sys, &filter, cli.mig_id_format, cli.sort_by)
+                    {
                         // Clear screen and write buffer
                         execute!(stdout, MoveTo(0, 0), Clear(ClearType::All)).unwrap();
                         write!(stdout, "{}", buffer).unwrap();
@@ -76,34 +194,48 @@ fn main() -> Result<(), NvmlError> {
                 // On exit, restore cursor visibility
                 execute!(stdout, Show).unwrap();
             } else {
-                if let Ok(buffer) = get_metrics(&device, &sys) {
+                if let Ok(buffer) =
+                    get_metrics(&nvml, &sys, &filter, cli.mig_id_format, cli.sort_by)
+                {
                     write!(stdout, "{}", buffer).unwrap();
                     stdout.flush().unwrap();
                 }
             }
         }
         Some(Commands::Info) => {
-            // println!("{:<15}: {:?}", "Brand", device.brand()?);
-
-            // println!("{:<15}: {}", "Name", device.name()?);
-            // println!(
-            //     "{:<15}:  {} (watts) ",
-            //     "Power Limit",
-            //     (device.enforced_power_limit()? / 1000)
-            // );
-
-            // let (total_mem, _, _) = get_gpu_memory_utilization(&device)?;
-            // println!("{:<15}:  {} (MiB)", "Total GPU Memory", total_mem);
-            println!("{:<16}: {:?}", "Brand", device.brand()?);
-            println!("{:<16}: {}", "Name", device.name()?);
-            println!(
-                "{:<16}: {} (watts)",
-                "Power Limit",
-                device.enforced_power_limit()? / 1000
-            );
-
-            let (total_mem, _, _) = get_gpu_memory_utilization(&device)?;
-            println!("{:<16}: {} (MiB)", "Total GPU Memory", total_mem);
+            let count = nvml.device_count()?;
+            for index in 0..count {
+                let device = nvml.device_by_index(index)?;
+                if filter.excludes_device(index, &device.name()?) {
+                    continue;
+                }
+
+                println!("GPU {}", index);
+                println!("{:<16}: {:?}", "Brand", device.brand()?);
+                println!("{:<16}: {}", "Name", device.name()?);
+                if !filter.excludes_metric(Metric::Power) {
+                    println!(
+                        "{:<16}: {} (watts)",
+                        "Power Limit",
+                        device.enforced_power_limit()? / 1000
+                    );
+                }
+
+                let (total_mem, _, _) = get_gpu_memory_utilization(&device)?;
+                println!("{:<16}: {} (MiB)", "Total GPU Memory", total_mem);
+                println!();
+            }
+        }
+        Some(Commands::Export { format }) => {
+            sys.refresh_processes(ProcessesToUpdate::All, true);
+
+            match format {
+                ExportFormat::Line => {
+                    let buffer = get_metrics_line_protocol(&nvml, &sys, &filter)?;
+                    write!(stdout, "{}", buffer).unwrap();
+                    stdout.flush().unwrap();
+                }
+            }
         }
         None => {}
     }
@@ -111,29 +243,244 @@ fn main() -> Result<(), NvmlError> {
     Ok(())
 }
 
-fn get_metrics(device: &Device, sys: &System) -> Result<String, NvmlError> {
+fn get_metrics(
+    nvml: &Nvml,
+    sys: &System,
+    filter: &ExcludeFilter,
+    mig_id_format: MigIdFormat,
+    sort_by: SortBy,
+) -> Result<String, NvmlError> {
+    let mut buffer = String::new();
+    let count = nvml.device_count()?;
+
+    for index in 0..count {
+        let device = nvml.device_by_index(index)?;
+        if filter.excludes_device(index, &device.name()?) {
+            continue;
+        }
+        buffer.push_str(&get_device_metrics(
+            index,
+            &device,
+            sys,
+            filter,
+            mig_id_format,
+            sort_by,
+        )?);
+    }
+
+    Ok(buffer)
+}
+
+fn get_device_metrics(
+    index: u32,
+    device: &Device,
+    sys: &System,
+    filter: &ExcludeFilter,
+    mig_id_format: MigIdFormat,
+    sort_by: SortBy,
+) -> Result<String, NvmlError> {
     // Framebuffer string
     let mut buffer = String::new();
-    let utilization = device.utilization_rates()?;
-    let (total_mib, used_mib, free_mib) = get_gpu_memory_utilization(&device)?;
+    // nvmlDeviceGetUtilizationRates and nvmlDeviceGetClockInfo both return
+    // NotSupported on a MIG-mode-enabled parent device, so check MIG mode
+    // before touching any whole-card query.
+    let mig_enabled = device.is_mig_mode_enabled()?;
+
+    buffer.push_str(&format!("GPU {}: {}\n", index, device.name()?));
+    buffer.push_str("===========================\n\n");
+
+    if !mig_enabled {
+        let utilization = device.utilization_rates()?;
+        buffer.push_str(&format!("Overall GPU utilization: {}%\n", utilization.gpu));
+        buffer.push_str("---------------------------\n\n");
+
+        let (total_mib, used_mib, free_mib) = get_gpu_memory_utilization(device)?;
+        buffer.push_str(&format!(
+            "GPU Memory Usage: {} MiB used / {} MiB total ({} MiB free)\n",
+            used_mib, total_mib, free_mib
+        ));
+        buffer.push_str("---------------------------\n\n");
+    }
+
+    if !filter.excludes_metric(Metric::Temperature) || !filter.excludes_metric(Metric::Power) {
+        buffer.push_str("Thermal/Power\n");
+        buffer.push_str("---------------------------\n\n");
+        if !filter.excludes_metric(Metric::Temperature) {
+            let temp = device.temperature(TemperatureSensor::Gpu)?;
+            buffer.push_str(&format!("GPU Temperature: {} C\n", temp));
+        }
+        if !filter.excludes_metric(Metric::Power) {
+            let power_watts = device.power_usage()? / 1000;
+            let power_limit_watts = device.enforced_power_limit()? / 1000;
+            buffer.push_str(&format!(
+                "Power Usage: {} W / {} W\n",
+                power_watts, power_limit_watts
+            ));
+        }
+        buffer.push_str("---------------------------\n\n");
+    }
+
+    if !mig_enabled && !filter.excludes_metric(Metric::Clocks) {
+        let graphics_clock = device.clock(Clock::Graphics, ClockId::Current)?;
+        let sm_clock = device.clock(Clock::SM, ClockId::Current)?;
+        let mem_clock = device.clock(Clock::Memory, ClockId::Current)?;
+        let video_clock = device.clock(Clock::Video, ClockId::Current)?;
+        buffer.push_str("Clocks (MHz)\n");
+        buffer.push_str("---------------------------\n\n");
+        buffer.push_str(&format!(
+            "Graphics: {:<6} SM: {:<6} Memory: {:<6} Video: {:<6}\n",
+            graphics_clock, sm_clock, mem_clock, video_clock
+        ));
+        buffer.push_str("---------------------------\n\n");
+    }
+
+    if mig_enabled {
+        buffer.push_str(&get_mig_metrics(index, device, mig_id_format)?);
+    }
+
+    if !filter.excludes_metric(Metric::Processes) {
+        buffer.push_str("Processes using GPU memory:\n");
+        buffer.push_str("---------------------------\n\n");
+        buffer.push_str(&format!(
+            "{:<5} {:<8} {:<24} {:<16} {:<8}\n",
+            "GPU", "PID", "NAME", "GPU Memory (MiB)", "TYPE"
+        ));
+
+        get_gpu_processes(index, device, sys, sort_by)?
+            .iter()
+            .for_each(|p| buffer.push_str(p));
+    }
+    buffer.push('\n');
+
+    Ok(buffer)
+}
+
+fn get_metrics_line_protocol(
+    nvml: &Nvml,
+    sys: &System,
+    filter: &ExcludeFilter,
+) -> Result<String, NvmlError> {
+    let mut buffer = String::new();
+    let count = nvml.device_count()?;
+    let timestamp = unix_nanos();
+
+    for index in 0..count {
+        let device = nvml.device_by_index(index)?;
+        if filter.excludes_device(index, &device.name()?) {
+            continue;
+        }
+        buffer.push_str(&get_device_line_protocol(index, &device, sys, timestamp, filter)?);
+    }
+
+    Ok(buffer)
+}
+
+fn get_device_line_protocol(
+    index: u32,
+    device: &Device,
+    sys: &System,
+    timestamp: u128,
+    filter: &ExcludeFilter,
+) -> Result<String, NvmlError> {
+    let mut buffer = String::new();
+    let name = device.name()?;
+    // nvmlDeviceGetUtilizationRates returns NotSupported on a MIG-mode-enabled
+    // parent device, so skip it there rather than erroring out the whole sample.
+    let mig_enabled = device.is_mig_mode_enabled()?;
+    let (total_mib, used_mib, _) = get_gpu_memory_utilization(device)?;
+
+    let mut fields = format!("mem_used={}i,mem_total={}i", used_mib, total_mib);
+    if !mig_enabled {
+        let utilization = device.utilization_rates()?;
+        fields.push_str(&format!(",utilization={}i", utilization.gpu));
+    }
+    if !filter.excludes_metric(Metric::Power) {
+        let power_watts = device.power_usage()? / 1000;
+        fields.push_str(&format!(",power_watts={}i", power_watts));
+    }
 
-    buffer.push_str(&format!("Overall GPU utilization: {}%\n", utilization.gpu));
-    buffer.push_str("---------------------------\n\n");
     buffer.push_str(&format!(
-        "GPU Memory Usage: {} MiB used / {} MiB total ({} MiB free)\n",
-        used_mib, total_mib, free_mib
+        "grax_gpu,index={},name={} {} {}\n",
+        index,
+        escape_tag(&name),
+        fields,
+        timestamp,
     ));
-    buffer.push_str("---------------------------\n\n");
-    buffer.push_str("Processes using GPU memory:\n");
+
+    if !filter.excludes_metric(Metric::Processes) {
+        let processes = merge_gpu_processes(device)?;
+
+        for (pid, (mem, ty)) in processes {
+            let name = get_process_name(sys, pid);
+            let mem_mib = match mem {
+                UsedGpuMemory::Used(bytes) => bytes / 1024 / 1024,
+                UsedGpuMemory::Unavailable => 0,
+            };
+            buffer.push_str(&format!(
+                "grax_gpu_process,index={},pid={},name={},type={} mem_used={}i {}\n",
+                index,
+                pid,
+                escape_tag(&name),
+                ty.to_string().to_lowercase(),
+                mem_mib,
+                timestamp,
+            ));
+        }
+    }
+
+    Ok(buffer)
+}
+
+/// Escapes characters that are significant to InfluxDB line protocol tag sets
+/// (commas, spaces, and equals signs) with a backslash.
+fn escape_tag(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace(',', "\\,")
+        .replace(' ', "\\ ")
+        .replace('=', "\\=")
+}
+
+fn unix_nanos() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos()
+}
+
+/// Reports memory usage per MIG instance rather than collapsing MIG
+/// partitions into the parent device's totals. NVML does not expose a
+/// per-instance utilization query (`nvmlDeviceGetUtilizationRates` is not
+/// supported on MIG instance handles), so utilization is omitted here rather
+/// than faked from the parent device's aggregate figure.
+fn get_mig_metrics(
+    index: u32,
+    device: &Device,
+    id_format: MigIdFormat,
+) -> Result<String, NvmlError> {
+    let mut buffer = String::new();
+    let mig_count = device.mig_device_count()?;
+
+    buffer.push_str("MIG Instances\n");
     buffer.push_str("---------------------------\n\n");
     buffer.push_str(&format!(
-        "{:<8} {:<24} {:<16}\n",
-        "PID", "NAME", "GPU Memory (MiB)"
+        "{:<24} {:<16} {:<16}\n",
+        "INSTANCE", "MEM USED (MiB)", "MEM TOTAL (MiB)"
     ));
 
-    get_gpu_processes(device, sys)?
-        .iter()
-        .for_each(|p| buffer.push_str(p));
+    for mig_index in 0..mig_count {
+        let mig = device.mig_device(mig_index)?;
+        let id = match id_format {
+            MigIdFormat::Uuid => mig.uuid()?,
+            MigIdFormat::Slice => format!("{}/{}", index, mig.gpu_instance_id()?),
+        };
+        let mem_info = mig.memory_info()?;
+        let used_mib = mem_info.used / 1024 / 1024;
+        let total_mib = mem_info.total / 1024 / 1024;
+
+        buffer.push_str(&format!("{:<24} {:<16} {:<16}\n", id, used_mib, total_mib));
+    }
+    buffer.push_str("---------------------------\n\n");
 
     Ok(buffer)
 }
@@ -155,31 +502,99 @@ fn get_process_name(sys: &System, pid: u32) -> String {
         .unwrap_or_else(|| "unknown".to_string())
 }
 
-fn get_gpu_processes(device: &Device, sys: &System) -> Result<Vec<String>, NvmlError> {
+/// Classifies which NVML process list(s) a PID was reported in.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum ProcessType {
+    Compute,
+    Graphics,
+    Both,
+}
+
+impl std::fmt::Display for ProcessType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            ProcessType::Compute => "Compute",
+            ProcessType::Graphics => "Graphics",
+            ProcessType::Both => "Both",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+/// Merges the compute and graphics process lists, keeping the memory figure
+/// and tagging the type as `Both` when a PID appears in both lists rather
+/// than silently keeping only the first one seen.
+fn merge_gpu_processes(device: &Device) -> Result<HashMap<u32, (UsedGpuMemory, ProcessType)>, NvmlError> {
     let mut processes = HashMap::new();
-    let mut sorted_processes = Vec::new();
     for proc in device.running_compute_processes()? {
-        processes.insert(proc.pid, proc.used_gpu_memory);
+        processes.insert(proc.pid, (proc.used_gpu_memory, ProcessType::Compute));
     }
 
     for proc in device.running_graphics_processes()? {
-        processes.entry(proc.pid).or_insert(proc.used_gpu_memory);
-    }
-
-    if !processes.is_empty() {
-        sorted_processes = processes
-            .into_iter()
-            .map(|(pid, mem)| {
-                let name = get_process_name(&sys, pid);
-                let mem = match mem {
-                    UsedGpuMemory::Used(bytes) => bytes / 1024 / 1024,
-                    UsedGpuMemory::Unavailable => 0,
-                };
-                format!("{:<8} {:<24} {:<16}\n", pid, name, mem)
+        processes
+            .entry(proc.pid)
+            .and_modify(|(_, ty)| {
+                if *ty == ProcessType::Compute {
+                    *ty = ProcessType::Both;
+                }
             })
-            .collect();
-        sorted_processes.sort_by(|a, b| a.to_lowercase().cmp(&b.to_lowercase()));
+            .or_insert((proc.used_gpu_memory, ProcessType::Graphics));
     }
 
-    Ok(sorted_processes)
+    Ok(processes)
+}
+
+/// A single row of the process table, kept structured so it can be sorted
+/// on its real fields rather than on the formatted string.
+struct GpuProcessRow {
+    pid: u32,
+    name: String,
+    mem_mib: u64,
+    ty: ProcessType,
+}
+
+fn get_gpu_processes(
+    index: u32,
+    device: &Device,
+    sys: &System,
+    sort_by: SortBy,
+) -> Result<Vec<String>, NvmlError> {
+    let processes = merge_gpu_processes(device)?;
+
+    let mut rows: Vec<GpuProcessRow> = processes
+        .into_iter()
+        .map(|(pid, (mem, ty))| {
+            let name = get_process_name(sys, pid);
+            let mem_mib = match mem {
+                UsedGpuMemory::Used(bytes) => bytes / 1024 / 1024,
+                UsedGpuMemory::Unavailable => 0,
+            };
+            GpuProcessRow {
+                pid,
+                name,
+                mem_mib,
+                ty,
+            }
+        })
+        .collect();
+
+    rows.sort_by(|a, b| {
+        let ordering = match sort_by {
+            SortBy::Memory => b.mem_mib.cmp(&a.mem_mib),
+            SortBy::Pid => a.pid.cmp(&b.pid),
+            SortBy::Name => a.name.to_lowercase().cmp(&b.name.to_lowercase()),
+            SortBy::Type => a.ty.to_string().cmp(&b.ty.to_string()),
+        };
+        ordering.then_with(|| a.pid.cmp(&b.pid))
+    });
+
+    Ok(rows
+        .iter()
+        .map(|r| {
+            format!(
+                "{:<5} {:<8} {:<24} {:<16} {:<8}\n",
+                index, r.pid, r.name, r.mem_mib, r.ty
+            )
+        })
+        .collect())
 }